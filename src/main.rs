@@ -4,14 +4,22 @@ use skim::prelude::*;
 use std::path::PathBuf;
 use std::collections::HashMap;
 use serde::{Serialize, Deserialize};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 mod runner;
 use runner::Runner;
 
+#[derive(Serialize, Deserialize, Debug)]
+struct CachedRunner {
+    runner: Runner,
+    /// Fingerprint of the config files that produced `runner`, so a stale
+    /// entry can be detected once those files change.
+    fingerprint: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 struct RunnerCache {
-    runners: HashMap<PathBuf, Runner>,
+    runners: HashMap<PathBuf, CachedRunner>,
 }
 
 #[derive(Parser, Debug)]
@@ -20,6 +28,21 @@ struct Cli {
     #[arg(short, long="force-choose", help="Force runfast to choose a new runner, instead of \
         looking for one that may already be set")]
     force_choose_new: bool,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Write a starter runners.toml next to the generated defaults.toml
+    Init,
+    /// Print the fully merged, effective runner set to stdout as TOML
+    DumpConfig {
+        /// Only emit fields that differ from the shipped defaults
+        #[arg(long)]
+        minimal: bool,
+    },
 }
 
 impl RunnerCache {
@@ -48,19 +71,30 @@ impl RunnerCache {
         }
     }
 
-    fn try_get_runner(&self) -> Option<Runner> {
-        match self.runners.get(&std::env::current_dir().unwrap()) {
-            Some(rnr) => Some(rnr.to_owned()),
-            None => None,
+    /// Returns the cached runner for the current directory, unless the
+    /// config that produced it has since changed or it was renamed/removed
+    /// from the freshly loaded `current_runners`.
+    fn try_get_runner(&self, current_runners: &[Runner]) -> Option<Runner> {
+        let cached = self.runners.get(&std::env::current_dir().unwrap())?;
+
+        if cached.fingerprint != runner::config_fingerprint() {
+            return None;
+        }
+
+        if !current_runners.iter().any(|r| r.name == cached.runner.name) {
+            return None;
         }
+
+        Some(cached.runner.clone())
     }
 
     fn add_runner(&mut self, runner: &Runner) {
         let current_path = std::env::current_dir().unwrap();
-        if self.runners.contains_key(&current_path) {
-            self.runners.remove(&current_path);
-        }
-        self.runners.insert(current_path, runner.clone());
+        let cached = CachedRunner {
+            runner: runner.clone(),
+            fingerprint: runner::config_fingerprint(),
+        };
+        self.runners.insert(current_path, cached);
 
         let new_cache = match toml::to_string(&self) {
             Ok(nc) => nc,
@@ -85,13 +119,22 @@ impl RunnerCache {
 pub fn main() {
     let cli = Cli::parse();
 
+    if let Some(command) = &cli.command {
+        match command {
+            Commands::Init => runner::init_user_config(),
+            Commands::DumpConfig { minimal } => println!("{}", runner::dump_config(*minimal)),
+        }
+        return;
+    }
+
     let mut cache = RunnerCache::load();
+    let runners = runner::load_runners();
 
     let chosen;
 
     // TODO: this is disgusting there must be a better way
     if cli.force_choose_new {
-        chosen = select_new_runner();
+        chosen = select_new_runner(runners);
         if chosen.is_some() {
             if cache.is_some() {
                 cache.as_mut().unwrap().add_runner(&chosen.as_ref().unwrap());
@@ -103,33 +146,35 @@ pub fn main() {
         }
     } else {
         chosen = match cache {
-            Some(ref mut c) => match c.try_get_runner() {
+            Some(ref mut c) => match c.try_get_runner(&runners) {
                 Some(rnr) => Some(rnr), // runner found in the cache
                 None => { // runner not found in the cache
-                    let rnr = select_new_runner();
+                    let rnr = select_new_runner(runners);
                     if rnr.is_some() {
                         c.add_runner(&rnr.as_ref().unwrap());
                     }
                     rnr
                 },
             },
-            None => select_new_runner(),
+            None => select_new_runner(runners),
         };
     }
 
 
-    match chosen {
+    let exit_code = match chosen {
         Some(cr) => cr.run(),
-        None => println!("No Runner Selected"),
+        None => {
+            println!("No Runner Selected");
+            0
+        },
     };
 
     println!("bye!");
 
+    std::process::exit(exit_code);
 }
 
-fn select_new_runner() -> Option<Runner> {
-    let runners = runner::load_runners();
-
+fn select_new_runner(runners: Vec<Runner>) -> Option<Runner> {
     let options = SkimOptionsBuilder::default()
         .preview(Some(""))
         .preview_window(Some(""))
@@ -1,13 +1,17 @@
 use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::fs::File;
 use std::fs::read_to_string;
-use std::io::Write;
-use std::path::Path;
+use std::hash::{Hash, Hasher};
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use directories::BaseDirs;
 use serde::{Serialize, Deserialize};
 
 use skim::*;
+use skim::prelude::*;
 
 /// Holds all state required by a runner to execute a command
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -15,16 +19,49 @@ pub struct Runner {
     /// The name to call the runner in the TUI, for searching / selecting
     pub name: String,
 
-    /// The command to execute at run-time
+    /// The command to execute at run-time. May contain `{{name}}` placeholder
+    /// tokens, resolved via `vars` (or a raw prompt) before execution.
     pub cmd: String,
 
     /// False if runfast should prompt for an extra ENTER press before exiting.
     pub quit_fast: bool,
+
+    /// The config file this runner definition was merged from, i.e. the
+    /// highest-precedence file that declared it.
+    pub source: PathBuf,
+
+    /// Declares how to resolve each `{{name}}` placeholder found in `cmd`.
+    #[serde(default)]
+    pub vars: HashMap<String, VarConfig>,
+
+    /// The interpreter to run `cmd` with, e.g. `["bash", "-c"]` or
+    /// `["fish", "-c"]`. Defaults to `bash -c`.
+    pub shell: Vec<String>,
+
+    /// Working directory to run `cmd` in. Defaults to the current directory.
+    pub cwd: Option<PathBuf>,
+
+    /// Extra environment variables applied to the spawned command.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+/// `Runner::shell` when a config doesn't specify one.
+fn default_shell() -> Vec<String> {
+    vec!["bash".to_string(), "-c".to_string()]
+}
+
+/// Declares how a single `{{name}}` placeholder should be resolved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VarConfig {
+    /// Shell command whose stdout lines become the candidate list offered to
+    /// a skim picker. When absent, the user is prompted for raw input.
+    pub suggestion: Option<String>,
 }
 
 impl Runner {
     /// Returns a `Runner`, filling in any blanks with defaults.
-    fn new_from_config(conf: &RunnerConfig) -> Runner {
+    fn new_from_config(conf: &RunnerConfig, source: &Path) -> Runner {
         Runner {
             name: match &conf.name {
                 Some(n) => n.clone(),
@@ -34,24 +71,172 @@ impl Runner {
                 Some(c) => c.clone(),
                 None => "echo 'command not set'".to_string(),
             },
-            quit_fast: conf.quit_fast.unwrap_or(false)
+            quit_fast: conf.quit_fast.unwrap_or(false),
+            source: source.to_path_buf(),
+            vars: conf.vars.clone().unwrap_or_default(),
+            shell: match &conf.shell {
+                // an explicit `shell = []` is as unusable as an unset one
+                Some(s) if !s.is_empty() => s.clone(),
+                _ => default_shell(),
+            },
+            cwd: conf.cwd.clone(),
+            env: conf.env.clone().unwrap_or_default(),
         }
     }
 
-    /// Uses this runner to execute the run command
-    pub fn run(&self) {
-        let mut c = Command::new("bash");
-        c.arg("-c");
-        c.arg(&self.cmd);
-        let result = c.status();
-        if result.is_err() {
-            println!("Error Running Command: {:#?}", result);
+    /// Uses this runner to execute the run command, returning the exit code
+    /// runfast itself should exit with so it composes in scripts.
+    pub fn run(&self) -> i32 {
+        let resolved = match self.resolve_vars() {
+            Ok(resolved) => resolved,
+            Err(e) => {
+                println!("Error resolving variables: {}", e);
+                return 1;
+            }
+        };
+        let cmd = substitute_vars(&self.cmd, &resolved);
+
+        let mut shell_args = self.shell.iter();
+        let program = shell_args.next().cloned().unwrap_or_else(|| "bash".to_string());
+
+        let mut c = Command::new(&program);
+        c.args(shell_args);
+        c.arg(&cmd);
+        if let Some(cwd) = &self.cwd {
+            c.current_dir(cwd);
         }
+        c.envs(&self.env);
+
+        let exit_code = match c.status() {
+            Ok(status) => status.code().unwrap_or(1),
+            Err(e) => {
+                println!("failed to spawn `{}`: {}", program, e);
+                1
+            }
+        };
+
         if !self.quit_fast {
             println!("Press ENTER to exit...");
             let _ = Command::new("bash").arg("-c").arg("read").status();
         }
+
+        exit_code
     }
+
+    /// Scans `cmd` for `{{name}}` placeholders and resolves each one, either
+    /// via its declared suggestion picker or a raw text prompt.
+    fn resolve_vars(&self) -> Result<HashMap<String, String>, String> {
+        let mut resolved = HashMap::new();
+
+        for token in extract_var_tokens(&self.cmd) {
+            let suggestion = self.vars.get(&token).and_then(|v| v.suggestion.as_ref());
+            let value = match suggestion {
+                Some(suggestion_cmd) => prompt_with_suggestion(&token, suggestion_cmd)?,
+                None => prompt_raw(&token),
+            };
+            resolved.insert(token, value);
+        }
+
+        Ok(resolved)
+    }
+}
+
+/// Extracts the distinct `{{name}}` placeholder tokens from `cmd`, in the
+/// order they first appear.
+fn extract_var_tokens(cmd: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut rest = cmd;
+
+    while let Some(start) = rest.find("{{") {
+        let after_start = &rest[start + 2..];
+        let end = match after_start.find("}}") {
+            Some(end) => end,
+            None => break,
+        };
+
+        let name = after_start[..end].to_string();
+        if !name.is_empty() && !tokens.contains(&name) {
+            tokens.push(name);
+        }
+        rest = &after_start[end + 2..];
+    }
+
+    tokens
+}
+
+/// Runs `suggestion_cmd`, offers its stdout lines through a skim picker, and
+/// falls back to a raw text prompt if nothing is selected.
+fn prompt_with_suggestion(token: &str, suggestion_cmd: &str) -> Result<String, String> {
+    let output = Command::new("bash").arg("-c").arg(suggestion_cmd).output()
+        .map_err(|e| format!("failed to run suggestion command for `{{{{{}}}}}`: {}", token, e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "suggestion command for `{{{{{}}}}}` exited with {}: {}",
+            token,
+            output.status,
+            String::from_utf8_lossy(&output.stderr),
+        ));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+
+    let prompt = format!("{}> ", token);
+    let options = SkimOptionsBuilder::default()
+        .prompt(Some(&prompt))
+        .build()
+        .unwrap();
+
+    let item_reader = SkimItemReader::default();
+    let items = item_reader.of_bufread(Cursor::new(stdout));
+
+    match Skim::run_with(&options, Some(items)) {
+        Some(out) if out.final_event != Event::EvActAbort && !out.selected_items.is_empty() => {
+            Ok(out.selected_items[0].output().to_string())
+        }
+        _ => Ok(prompt_raw(token)),
+    }
+}
+
+/// Prompts for raw text input on stdin.
+fn prompt_raw(token: &str) -> String {
+    print!("{}: ", token);
+    let _ = std::io::stdout().flush();
+    let mut input = String::new();
+    let _ = std::io::stdin().read_line(&mut input);
+    input.trim().to_string()
+}
+
+/// Shell-escapes `value` by single-quoting it, so substitution can't break
+/// the surrounding command's quoting.
+fn shell_escape(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Literally replaces each resolved `{{name}}` token in `cmd` with its
+/// shell-escaped value, in a single pass over `cmd`.
+fn substitute_vars(cmd: &str, resolved: &HashMap<String, String>) -> String {
+    let mut result = String::new();
+    let mut rest = cmd;
+
+    while let Some(start) = rest.find("{{") {
+        let after_start = &rest[start + 2..];
+        let end = match after_start.find("}}") {
+            Some(end) => end,
+            None => break,
+        };
+
+        let name = &after_start[..end];
+        result.push_str(&rest[..start]);
+        match resolved.get(name) {
+            Some(value) => result.push_str(&shell_escape(value)),
+            None => result.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after_start[end + 2..];
+    }
+
+    result.push_str(rest);
+    result
 }
 
 impl SkimItem for Runner {
@@ -75,7 +260,19 @@ impl SkimItem for Runner {
         prev.push_str(&self.quit_fast.to_string());
         prev.push('\n');
 
-        ItemPreview::Text(prev)
+        prev.push_str("\n[SOURCE]\n");
+        prev.push_str(&self.source.display().to_string());
+        prev.push('\n');
+
+        let tokens = extract_var_tokens(&self.cmd);
+        if !tokens.is_empty() {
+            prev.push_str("\n[VARS]\n");
+            for token in &tokens {
+                prev.push_str(&format!("\x1b[33m{{{{{}}}}}\x1b[0m\n", token));
+            }
+        }
+
+        ItemPreview::AnsiText(prev)
     }
 }
 
@@ -94,69 +291,266 @@ struct RunnerConfig {
     name: Option<String>,
     cmd: Option<String>,
     quit_fast: Option<bool>,
+    vars: Option<HashMap<String, VarConfig>>,
+    shell: Option<Vec<String>>,
+    cwd: Option<PathBuf>,
+    env: Option<HashMap<String, String>>,
 }
 
-pub fn load_runners() -> Vec<Runner> {
-    // try to load ~/.config/runfast/defaults.toml and ~/.config/runfast/runners.toml
-    // prefer values in runners.toml if there are clashes
-    let base_dirs = BaseDirs::new().unwrap();
+impl RunnerConfig {
+    /// Fills any field left `None` in `self` with the corresponding value from
+    /// a lower-precedence definition of the same runner. Fields already set on
+    /// `self` are left untouched, except an empty `shell`, which counts as unset.
+    fn fill_from(&mut self, lower: &RunnerConfig) {
+        if self.cmd.is_none() {
+            self.cmd = lower.cmd.clone();
+        }
+        if self.quit_fast.is_none() {
+            self.quit_fast = lower.quit_fast;
+        }
+        if self.vars.is_none() {
+            self.vars = lower.vars.clone();
+        }
+        // an explicit `shell = []` is as unusable as an unset one, so it
+        // should still defer to a lower-precedence definition
+        if self.shell.as_ref().map_or(true, |s| s.is_empty()) {
+            self.shell = lower.shell.clone();
+        }
+        if self.cwd.is_none() {
+            self.cwd = lower.cwd.clone();
+        }
+        if self.env.is_none() {
+            self.env = lower.env.clone();
+        }
+    }
+}
 
-    // get default config directory (usually ~/.config/)
+/// Builds the ordered list of config files that make up the effective
+/// runner set, highest precedence first: innermost discovered project
+/// config outward, then the user config, then the shipped default.
+/// Generates the default config on disk if it doesn't exist yet.
+fn config_search_paths() -> Vec<PathBuf> {
+    let base_dirs = BaseDirs::new().unwrap();
     let confdir = base_dirs.config_dir();
 
-    // load default config
     let default_path = confdir.join("runfast/defaults.toml");
     if !default_path.exists() {
         generate_default_config(&default_path);
     }
-    let default_confstring = read_to_string(default_path).unwrap();
-    let default_configs = match toml::from_str::<Config>(&default_confstring) {
-        Ok(conf) => Some(conf),
-        Err(e) => panic!("Could not parse default config: {}", e),
-    };
 
-    // load user config
     let userconf_path = confdir.join("runfast/runners.toml");
-    let mut user_configs: Option<Config> = None;
-    if userconf_path.exists() {
-        let user_confstring = read_to_string(userconf_path).unwrap();
-        match toml::from_str::<Config>(&user_confstring) {
-            Ok(conf) => user_configs = Some(conf),
-            Err(e) => panic!("Could not parse user config: {}", e),
+
+    let mut config_paths = discover_project_configs();
+    config_paths.push(userconf_path);
+    config_paths.push(default_path);
+    config_paths
+}
+
+pub fn load_runners() -> Vec<Runner> {
+    // try to load ~/.config/runfast/defaults.toml and ~/.config/runfast/runners.toml,
+    // plus any `.runfast.toml` discovered walking up from the current directory.
+    // precedence is closest-directory project config > user config > shipped default
+    let config_paths = config_search_paths();
+
+    // name -> (merged-so-far config, source of its highest-precedence definition)
+    let mut merged: Vec<(String, RunnerConfig, PathBuf)> = Vec::new();
+
+    for path in &config_paths {
+        for runc in parse_runner_configs(path) {
+            let name = match &runc.name {
+                Some(n) => n.clone(),
+                None => continue,
+            };
+
+            match merged.iter_mut().find(|(n, _, _)| n == &name) {
+                Some((_, existing, _)) => existing.fill_from(&runc),
+                None => merged.push((name, runc, path.clone())),
+            }
         }
     }
 
-    let mut runners = get_runners_from_config(&user_configs);
-    let mut default_runners = get_runners_from_config(&default_configs);
+    merged.into_iter()
+        .map(|(_, conf, source)| Runner::new_from_config(&conf, &source))
+        .collect()
+}
+
+/// Fingerprints the config files that currently make up the effective runner
+/// set, by hashing each file's path and last-modified time. A cached runner
+/// is only trustworthy as long as this fingerprint hasn't changed.
+pub fn config_fingerprint() -> String {
+    let mut hasher = DefaultHasher::new();
 
-    while !default_runners.is_empty() {
-        let dr = default_runners.pop().unwrap();
-        let mut already_exists = false;
-        for r in &runners {
-            if dr.name == r.name {
-                already_exists = true;
-                break;
+    for path in config_search_paths() {
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            path.hash(&mut hasher);
+            if let Ok(modified) = metadata.modified() {
+                modified.hash(&mut hasher);
             }
         }
-        if !already_exists {
-            runners.push(dr);
-        }
     }
-    runners
+
+    format!("{:x}", hasher.finish())
 }
 
-fn get_runners_from_config(conf: &Option<Config>) -> Vec<Runner> {
-    let mut runners:Vec<Runner> = Vec::new();
+/// Loads only the shipped `defaults.toml` runner set, with no project or user
+/// config merged on top. Used to diff against when dumping an effective
+/// config with `--minimal`.
+pub fn load_default_runners() -> Vec<Runner> {
+    let base_dirs = BaseDirs::new().unwrap();
+    let confdir = base_dirs.config_dir();
+
+    let default_path = confdir.join("runfast/defaults.toml");
+    if !default_path.exists() {
+        generate_default_config(&default_path);
+    }
+
+    parse_runner_configs(&default_path).into_iter()
+        .map(|rc| Runner::new_from_config(&rc, &default_path))
+        .collect()
+}
+
+/// Parses the `[[runners]]` entries out of a single config file. Returns an
+/// empty list if the file doesn't exist.
+fn parse_runner_configs(path: &Path) -> Vec<RunnerConfig> {
+    if !path.exists() {
+        return Vec::new();
+    }
+
+    let confstring = match read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => panic!("Could not read config at {}: {}", path.display(), e),
+    };
+    let conf = match toml::from_str::<Config>(&confstring) {
+        Ok(conf) => conf,
+        Err(e) => panic!("Could not parse config at {}: {}", path.display(), e),
+    };
+
+    conf.runners.unwrap_or_default()
+}
+
+/// Writes a starter `runners.toml` next to `defaults.toml`, without
+/// overwriting an existing one.
+pub fn init_user_config() {
+    let base_dirs = BaseDirs::new().unwrap();
+    let confdir = base_dirs.config_dir();
+
+    let default_path = confdir.join("runfast/defaults.toml");
+    if !default_path.exists() {
+        generate_default_config(&default_path);
+    }
+
+    let userconf_path = confdir.join("runfast/runners.toml");
+    if userconf_path.exists() {
+        println!("{} already exists, leaving it untouched", userconf_path.display());
+        return;
+    }
+
+    match std::fs::write(&userconf_path, STARTER_RUNNERS_TOML) {
+        Ok(_) => println!("Wrote starter config to {}", userconf_path.display()),
+        Err(e) => panic!("Could not write starter config to {}: {}", userconf_path.display(), e),
+    }
+}
+
+const STARTER_RUNNERS_TOML: &str = r#"# runfast user config
+#
+# Define your own runners here, or override fields of a default runner by
+# reusing its name. Run `runfast dump-config` to see the fully merged,
+# effective set.
+#
+# [[runners]]
+# name = "build"
+# cmd = "cargo build"
+# quit_fast = false
+"#;
+
+/// Mirrors `RunnerConfig`, but for writing the effective config back out as
+/// TOML rather than reading it in.
+#[derive(Debug, Serialize)]
+struct RunnerDump {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cmd: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quit_fast: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vars: Option<HashMap<String, VarConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shell: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cwd: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    env: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ConfigDump {
+    runners: Vec<RunnerDump>,
+}
 
-    if let Some(c) = conf {
-        if let Some(r) = &c.runners {
-            for runc in r {
-                runners.push(Runner::new_from_config(runc))
+/// Renders the fully merged, effective runner set as TOML. With `minimal`,
+/// only fields that differ from the shipped default of the same name are
+/// emitted.
+pub fn dump_config(minimal: bool) -> String {
+    let runners = load_runners();
+    let defaults = load_default_runners();
+
+    let dumped: Vec<RunnerDump> = runners.iter()
+        .map(|r| {
+            let default = defaults.iter().find(|d| d.name == r.name);
+            if minimal {
+                RunnerDump {
+                    name: r.name.clone(),
+                    cmd: diff_field(&r.cmd, default.map(|d| &d.cmd)),
+                    quit_fast: diff_field(&r.quit_fast, default.map(|d| &d.quit_fast)),
+                    vars: diff_field(&r.vars, default.map(|d| &d.vars)),
+                    shell: diff_field(&r.shell, default.map(|d| &d.shell)),
+                    cwd: diff_field(&r.cwd, default.map(|d| &d.cwd)).flatten(),
+                    env: diff_field(&r.env, default.map(|d| &d.env)),
+                }
+            } else {
+                RunnerDump {
+                    name: r.name.clone(),
+                    cmd: Some(r.cmd.clone()),
+                    quit_fast: Some(r.quit_fast),
+                    vars: Some(r.vars.clone()),
+                    shell: Some(r.shell.clone()),
+                    cwd: r.cwd.clone(),
+                    env: Some(r.env.clone()),
+                }
             }
+        })
+        .collect();
+
+    match toml::to_string_pretty(&ConfigDump { runners: dumped }) {
+        Ok(s) => s,
+        Err(e) => panic!("Could not serialise effective config to toml: {}", e),
+    }
+}
+
+/// Returns `Some(value)` unless there's a default to compare against and it's
+/// equal to `value` — used to build the `--minimal` dump.
+fn diff_field<T: Clone + PartialEq>(value: &T, default: Option<&T>) -> Option<T> {
+    match default {
+        Some(d) if d == value => None,
+        _ => Some(value.clone()),
+    }
+}
+
+/// Walks upward from the current directory collecting any `.runfast.toml`
+/// files it finds, closest directory first.
+fn discover_project_configs() -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    let mut dir = std::env::current_dir().ok();
+
+    while let Some(d) = dir {
+        let candidate = d.join(".runfast.toml");
+        if candidate.exists() {
+            found.push(candidate);
         }
+        dir = d.parent().map(PathBuf::from);
     }
 
-    runners
+    found
 }
 
 fn generate_default_config(default_path: &Path) {
@@ -179,3 +573,109 @@ fn generate_default_config(default_path: &Path) {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_config() -> RunnerConfig {
+        RunnerConfig {
+            name: None,
+            cmd: None,
+            quit_fast: None,
+            vars: None,
+            shell: None,
+            cwd: None,
+            env: None,
+        }
+    }
+
+    #[test]
+    fn fill_from_keeps_already_set_fields() {
+        let mut high = RunnerConfig { cmd: Some("high cmd".to_string()), ..empty_config() };
+        let low = RunnerConfig { cmd: Some("low cmd".to_string()), ..empty_config() };
+
+        high.fill_from(&low);
+
+        assert_eq!(high.cmd, Some("high cmd".to_string()));
+    }
+
+    #[test]
+    fn fill_from_inherits_unset_fields() {
+        let mut high = RunnerConfig { cmd: Some("high cmd".to_string()), ..empty_config() };
+        let low = RunnerConfig {
+            cmd: Some("low cmd".to_string()),
+            quit_fast: Some(true),
+            ..empty_config()
+        };
+
+        high.fill_from(&low);
+
+        assert_eq!(high.cmd, Some("high cmd".to_string()));
+        assert_eq!(high.quit_fast, Some(true));
+    }
+
+    #[test]
+    fn fill_from_treats_empty_shell_as_unset() {
+        let mut high = RunnerConfig { shell: Some(vec![]), ..empty_config() };
+        let low = RunnerConfig {
+            shell: Some(vec!["fish".to_string(), "-c".to_string()]),
+            ..empty_config()
+        };
+
+        high.fill_from(&low);
+
+        assert_eq!(high.shell, Some(vec!["fish".to_string(), "-c".to_string()]));
+    }
+
+    #[test]
+    fn fill_from_keeps_non_empty_shell() {
+        let mut high = RunnerConfig {
+            shell: Some(vec!["zsh".to_string(), "-c".to_string()]),
+            ..empty_config()
+        };
+        let low = RunnerConfig {
+            shell: Some(vec!["fish".to_string(), "-c".to_string()]),
+            ..empty_config()
+        };
+
+        high.fill_from(&low);
+
+        assert_eq!(high.shell, Some(vec!["zsh".to_string(), "-c".to_string()]));
+    }
+
+    #[test]
+    fn shell_escape_quotes_plain_values() {
+        assert_eq!(shell_escape("world"), "'world'");
+    }
+
+    #[test]
+    fn shell_escape_neutralises_quote_breaking_input() {
+        // a naive `'...'` wrap would let this close the quote early and run
+        // the rest as a second command
+        assert_eq!(shell_escape("x'; rm -rf /; echo '"), r"'x'\''; rm -rf /; echo '\'''");
+    }
+
+    #[test]
+    fn extract_var_tokens_dedupes_and_preserves_order() {
+        let tokens = extract_var_tokens("echo {{name}} {{env}} {{name}}");
+        assert_eq!(tokens, vec!["name".to_string(), "env".to_string()]);
+    }
+
+    #[test]
+    fn substitute_vars_replaces_and_escapes_each_token() {
+        let mut resolved = HashMap::new();
+        resolved.insert("name".to_string(), "world".to_string());
+
+        assert_eq!(substitute_vars("echo {{name}}", &resolved), "echo 'world'");
+    }
+
+    #[test]
+    fn substitute_vars_does_not_recurse_into_a_resolved_literal_token() {
+        let mut resolved = HashMap::new();
+        resolved.insert("name".to_string(), "{{other}}".to_string());
+        resolved.insert("other".to_string(), "x".to_string());
+
+        assert_eq!(substitute_vars("echo {{name}} {{other}}", &resolved), "echo '{{other}}' 'x'");
+    }
+}